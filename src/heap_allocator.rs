@@ -1,33 +1,71 @@
 pub use libc::{c_void, size_t};
+use std::alloc::{GlobalAlloc, Layout};
+use std::collections::BTreeMap;
 use std::fmt::{Debug, Formatter, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::memory_source::{DefaultMemorySource, MemorySource};
+use crate::reentrancy_guard::ReentrancyGuard;
+use crate::spin_lock::SpinLock;
+
+/// Alignment used by the plain (non-`GlobalAlloc`) C API, matching the heap's
+/// historical 4-byte granularity.
+const DEFAULT_ALIGN: usize = 4;
+
+/// Size of the first heap `HeapAllocator` lazily creates on first use.
+const INITIAL_HEAP_SIZE: size_t = 32768;
+
+/// Falls back to the system allocator for the nested allocations `Heap`'s own
+/// `Vec`/`BTreeMap` bookkeeping makes while `HeapAllocator`'s lock is already
+/// held (see `ReentrancyGuard`). `libc::malloc` only guarantees alignment
+/// suitable for any built-in type, so larger requests go through
+/// `posix_memalign` instead.
+fn system_alloc(size: size_t, align: usize) -> *mut c_void {
+    if align <= std::mem::size_of::<usize>() * 2 {
+        unsafe { libc::malloc(size) }
+    } else {
+        let mut ptr: *mut c_void = std::ptr::null_mut();
+        let status = unsafe { libc::posix_memalign(&mut ptr, align, size) };
+        if status == 0 {
+            ptr
+        } else {
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// How `Heap` picks a free region to satisfy an allocation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FitStrategy {
+    /// Use the first free region that is big enough.
+    FirstFit,
+    /// Scan every free region and use the smallest one that fits, to reduce
+    /// fragmentation at the cost of a full scan.
+    BestFit,
+}
 
 struct HeapAllocation {
+    /// Start of the reserved region. `padding` bytes precede the pointer
+    /// actually handed back to the caller.
     ptr: *mut c_void,
+    /// Bytes of alignment padding between `ptr` and the pointer returned to the caller.
+    padding: size_t,
     real_size: size_t,
     alloc_size: size_t,
 }
 
 impl HeapAllocation {
-    /// Returns the number of bytes between HeapAllocations
-    pub fn distance_to(&self, other: &HeapAllocation) -> i32 {
-        other.start() as i32 - self.end() as i32
-    }
-
     pub fn start(&self) -> usize {
         self.ptr as usize
     }
-
-    pub fn end(&self) -> usize {
-        return self.start() + self.alloc_size;
-    }
 }
 
 impl Debug for HeapAllocation {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         write!(
             f,
-            "HeapAllocation {{ ptr: {:?}, real_size: {}, alloc_size: {} }}",
-            self.ptr, self.real_size, self.alloc_size
+            "HeapAllocation {{ ptr: {:?}, padding: {}, real_size: {}, alloc_size: {} }}",
+            self.ptr, self.padding, self.real_size, self.alloc_size
         )
     }
 }
@@ -35,94 +73,196 @@ impl Debug for HeapAllocation {
 pub struct Heap {
     ptr: *mut c_void,
     size: size_t,
-    allocations: Vec<HeapAllocation>,
+    /// Live allocations keyed by the user-visible pointer (the address handed
+    /// back to the caller), so `free`/`realloc` are O(log n) instead of a
+    /// linear scan.
+    allocations: BTreeMap<usize, HeapAllocation>,
+    /// Free regions keyed by start address, so coalescing a freed region with
+    /// its neighbors is a couple of `range`-bounded lookups instead of a
+    /// linear scan, and splicing is an insert/remove instead of a `Vec` shift.
+    free_regions: BTreeMap<usize, size_t>,
+    fit_strategy: FitStrategy,
 }
 
 impl Heap {
     pub fn new(size: size_t) -> Heap {
-        let ptr = unsafe { libc::malloc(size) };
-        return Heap {
+        let ptr = DefaultMemorySource::default().reserve(size);
+        Heap {
             ptr,
             size,
-            allocations: vec![],
-        };
+            allocations: BTreeMap::new(),
+            free_regions: BTreeMap::from([(ptr as usize, size)]),
+            fit_strategy: FitStrategy::FirstFit,
+        }
+    }
+
+    pub fn set_fit_strategy(&mut self, strategy: FitStrategy) {
+        self.fit_strategy = strategy;
     }
 
     fn end(&self) -> usize {
-        return self.ptr as usize + self.size;
+        self.ptr as usize + self.size
     }
 
-    fn align(size: size_t) -> size_t {
-        return size + (size % 4);
+    /// Whether `ptr` falls within this heap's backing region.
+    fn owns(&self, ptr: *mut c_void) -> bool {
+        let addr = ptr as usize;
+        addr >= self.ptr as usize && addr < self.end()
+    }
+
+    /// Rounds `size` up to a multiple of `align`, which must be a power of two.
+    fn align(size: size_t, align: usize) -> size_t {
+        let align_mask = align - 1;
+        (size + align_mask) & !align_mask
     }
 
     fn used_space(&self) -> usize {
         let mut space = 0;
-        for allocation in self.allocations.iter() {
+        for allocation in self.allocations.values() {
             space += allocation.alloc_size;
         }
-        return space;
+        space
     }
+
     fn free_space(&self) -> usize {
-        return self.size - self.used_space();
+        self.free_regions.values().sum()
     }
 
-    fn next_for_size(&self, size: usize) -> (*mut c_void, usize) {
-        let mut ptr = self.ptr;
-        let mut previous: Option<&HeapAllocation> = None;
+    fn largest_free_block(&self) -> usize {
+        self.free_regions.values().copied().max().unwrap_or(0)
+    }
 
-        for (index, allocation) in self.allocations.iter().enumerate() {
-            if let Some(prev) = previous {
-                println!("{} {}", prev.distance_to(allocation), size);
-                if prev.distance_to(allocation) >= size as i32 {
-                    return (prev.end() as *mut c_void, index);
-                }
+    /// Finds a free region that can hold `size` bytes aligned to `align`,
+    /// according to `fit_strategy`. Returns the region's start address (its
+    /// key in `free_regions`), the aligned start address for the reservation,
+    /// and the padding before it.
+    fn find_region(&self, size: usize, align: usize) -> Option<(usize, usize, size_t)> {
+        let align_mask = align - 1;
+        let candidate = |start: usize, len: size_t| {
+            let aligned_start = (start + align_mask) & !align_mask;
+            if aligned_start + size <= start + len {
+                Some((aligned_start, aligned_start - start))
+            } else {
+                None
             }
-            previous = Some(allocation);
-            ptr = unsafe { ptr.add(allocation.alloc_size) };
+        };
+
+        match self.fit_strategy {
+            FitStrategy::FirstFit => self.free_regions.range(..).find_map(|(&start, &len)| {
+                candidate(start, len).map(|(aligned_start, padding)| (start, aligned_start, padding))
+            }),
+            FitStrategy::BestFit => self
+                .free_regions
+                .range(..)
+                .filter_map(|(&start, &len)| {
+                    candidate(start, len).map(|(aligned_start, padding)| (start, aligned_start, padding, len))
+                })
+                .min_by_key(|&(_, _, _, len)| len)
+                .map(|(start, aligned_start, padding, _)| (start, aligned_start, padding)),
+        }
+    }
+
+    /// Consumes `len` bytes from the start of the free region keyed by
+    /// `region_start`, removing the region entirely if nothing is left over.
+    fn consume_region(&mut self, region_start: usize, len: size_t) {
+        let region_len = self.free_regions.remove(&region_start).unwrap();
+        let remaining = region_len - len;
+        if remaining > 0 {
+            self.free_regions.insert(region_start + len, remaining);
         }
-        return (ptr, self.allocations.len());
     }
 
-    fn allocate(&mut self, size: size_t) -> *mut c_void {
-        let alloc_size = Self::align(size);
-        let (ptr, index) = self.next_for_size(alloc_size);
+    /// Adds `[start, start + len)` back to the free list, merging it with the
+    /// previous and/or next region if they're physically adjacent. The
+    /// neighbors are found with `range` lookups rather than a linear scan.
+    fn insert_free_region(&mut self, start: usize, len: size_t) {
+        let merges_prev = self
+            .free_regions
+            .range(..start)
+            .next_back()
+            .filter(|&(&prev_start, &prev_len)| prev_start + prev_len == start)
+            .map(|(&prev_start, _)| prev_start);
+
+        let merges_next = self.free_regions.contains_key(&(start + len));
 
-        if ptr as usize + alloc_size > self.end() {
-            return 0 as *mut c_void;
+        match (merges_prev, merges_next) {
+            (Some(prev_start), true) => {
+                let next_len = self.free_regions.remove(&(start + len)).unwrap();
+                *self.free_regions.get_mut(&prev_start).unwrap() += len + next_len;
+            }
+            (Some(prev_start), false) => {
+                *self.free_regions.get_mut(&prev_start).unwrap() += len;
+            }
+            (None, true) => {
+                let next_len = self.free_regions.remove(&(start + len)).unwrap();
+                self.free_regions.insert(start, len + next_len);
+            }
+            (None, false) => {
+                self.free_regions.insert(start, len);
+            }
         }
-        // TODO: Use log debug/info instead of println!
-        println!("allocating: {:?} {} bytes", ptr, alloc_size);
+    }
+
+    fn allocate_aligned(&mut self, size: size_t, align: usize) -> *mut c_void {
+        let aligned_size = Self::align(size, align);
+        let (region_start, aligned_start, padding) = match self.find_region(aligned_size, align) {
+            Some(found) => found,
+            None => return std::ptr::null_mut(),
+        };
+
+        let reservation = (aligned_start - padding) as *mut c_void;
+        let alloc_size = padding + aligned_size;
+        self.consume_region(region_start, alloc_size);
+
+        let user_ptr = aligned_start as *mut c_void;
+
         self.allocations.insert(
-            index,
+            user_ptr as usize,
             HeapAllocation {
-                ptr,
+                ptr: reservation,
+                padding,
                 real_size: size,
-                alloc_size: alloc_size,
+                alloc_size,
             },
         );
 
-        return ptr;
+        user_ptr
     }
 
     fn free(&mut self, ptr: *mut c_void) {
-        let mut index = None;
-        for (idx, allocation) in self.allocations.iter().enumerate() {
-            if allocation.ptr == ptr {
-                index = Some(idx);
-                break;
-            }
+        let alloc = match self.allocations.remove(&(ptr as usize)) {
+            Some(alloc) => alloc,
+            None => return,
+        };
+        self.insert_free_region(alloc.start(), alloc.alloc_size);
+    }
+
+    /// Extends or shrinks an existing allocation in place, growing into the free
+    /// region that immediately follows it when there's enough room, and handing
+    /// any shrunk tail back to the free list. Returns whether it succeeded.
+    fn try_grow(&mut self, ptr: *mut c_void, new_real_size: size_t, align: usize) -> bool {
+        let key = ptr as usize;
+        let (padding, old_alloc_size, start) = match self.allocations.get(&key) {
+            Some(allocation) => (allocation.padding, allocation.alloc_size, allocation.start()),
+            None => return false,
+        };
+        let new_alloc_size = padding + Self::align(new_real_size, align);
 
-            // allocations are contiguous, skip if we exceed the target ptr address
-            if allocation.ptr > ptr {
-                break;
+        if new_alloc_size > old_alloc_size {
+            let additional = new_alloc_size - old_alloc_size;
+            let alloc_end = start + old_alloc_size;
+            match self.free_regions.get(&alloc_end) {
+                Some(&len) if len >= additional => self.consume_region(alloc_end, additional),
+                _ => return false,
             }
+        } else if new_alloc_size < old_alloc_size {
+            self.insert_free_region(start + new_alloc_size, old_alloc_size - new_alloc_size);
         }
-        if let Some(idx) = index {
-            let alloc = self.allocations.remove(idx);
-            // TODO: Use log debug/info instead of println!
-            println!("freeing: {:?} {:?}", alloc.ptr, alloc.alloc_size);
-        }
+
+        let allocation = self.allocations.get_mut(&key).unwrap();
+        allocation.alloc_size = new_alloc_size;
+        allocation.real_size = new_real_size;
+        true
     }
 }
 
@@ -137,7 +277,7 @@ impl Debug for Heap {
             self.free_space(),
             self.allocations.len()
         )?;
-        for allocation in self.allocations.iter() {
+        for allocation in self.allocations.values() {
             write!(f, "\n\t{:?}", allocation)?;
         }
         Ok(())
@@ -145,32 +285,211 @@ impl Debug for Heap {
 }
 
 pub struct HeapAllocator {
-    pub heaps: Vec<Heap>,
+    heaps: SpinLock<Vec<Heap>>,
+    /// Fit strategy applied to every heap, including ones created later by growth.
+    best_fit: AtomicBool,
 }
 
+// `Heap` holds a raw `*mut c_void`, so it isn't `Send`/`Sync` on its own; access is
+// only ever made through `SpinLock`, which serializes it across threads.
+unsafe impl Send for HeapAllocator {}
+unsafe impl Sync for HeapAllocator {}
+
 impl HeapAllocator {
-    pub fn init_heap(&mut self, size: usize) {
-        self.heaps.push(Heap::new(size));
+    pub const fn new() -> HeapAllocator {
+        HeapAllocator {
+            heaps: SpinLock::new(Vec::new()),
+            best_fit: AtomicBool::new(false),
+        }
+    }
+
+    fn current_fit_strategy(&self) -> FitStrategy {
+        if self.best_fit.load(Ordering::Relaxed) {
+            FitStrategy::BestFit
+        } else {
+            FitStrategy::FirstFit
+        }
     }
-    pub fn allocate(&mut self, size: size_t) -> *mut c_void {
-        if self.heaps.len() == 0 {
-            self.init_heap(32768);
+
+    /// Sets the fit strategy used by every heap, including ones created later by growth.
+    pub fn set_fit_strategy(&self, strategy: FitStrategy) {
+        self.best_fit
+            .store(strategy == FitStrategy::BestFit, Ordering::Relaxed);
+        for heap in self.heaps.lock().iter_mut() {
+            heap.set_fit_strategy(strategy);
+        }
+    }
+
+    /// The largest contiguous free run across all heaps.
+    pub fn largest_free_block(&self) -> size_t {
+        self.heaps
+            .lock()
+            .iter()
+            .map(|heap| heap.largest_free_block())
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn init_heap(&self, size: usize) {
+        let _guard = ReentrancyGuard::enter();
+        let mut heap = Heap::new(size);
+        heap.set_fit_strategy(self.current_fit_strategy());
+        self.heaps.lock().push(heap);
+    }
+
+    pub fn allocate(&self, size: size_t) -> *mut c_void {
+        self.allocate_aligned(size, DEFAULT_ALIGN)
+    }
+
+    pub fn allocate_aligned(&self, size: size_t, align: usize) -> *mut c_void {
+        if align == 0 || !align.is_power_of_two() {
+            return std::ptr::null_mut();
+        }
+
+        let guard = ReentrancyGuard::enter();
+        if guard.is_reentrant() {
+            return system_alloc(size, align);
+        }
+
+        let mut heaps = self.heaps.lock();
+        if heaps.is_empty() {
+            let mut heap = Heap::new(INITIAL_HEAP_SIZE);
+            heap.set_fit_strategy(self.current_fit_strategy());
+            heaps.push(heap);
+        }
+
+        for heap in heaps.iter_mut() {
+            let ptr = heap.allocate_aligned(size, align);
+            if !ptr.is_null() {
+                return ptr;
+            }
+        }
+
+        // Every existing heap is full; grow the arena by adding another heap,
+        // at least big enough to satisfy this request.
+        let grown_size = heaps
+            .last()
+            .unwrap()
+            .size
+            .saturating_mul(2)
+            .max(Heap::align(size, align).saturating_add(align - 1));
+        let mut heap = Heap::new(grown_size);
+        heap.set_fit_strategy(self.current_fit_strategy());
+        heaps.push(heap);
+        heaps.last_mut().unwrap().allocate_aligned(size, align)
+    }
+
+    pub fn free(&self, ptr: *mut c_void) {
+        let guard = ReentrancyGuard::enter();
+        if guard.is_reentrant() {
+            unsafe { libc::free(ptr) };
+            return;
+        }
+
+        let mut heaps = self.heaps.lock();
+        if let Some(heap) = heaps.iter_mut().find(|heap| heap.owns(ptr)) {
+            heap.free(ptr);
+        }
+    }
+
+    /// Resizes an existing allocation, growing it in place when the gap that
+    /// follows it is large enough and falling back to allocate-copy-free otherwise.
+    fn realloc_aligned(&self, ptr: *mut c_void, align: usize, new_size: usize) -> *mut c_void {
+        enum Outcome {
+            GrewInPlace,
+            NotOwned,
+            FallBack(size_t),
+        }
+
+        let guard = ReentrancyGuard::enter();
+        if guard.is_reentrant() {
+            return unsafe { libc::realloc(ptr, new_size) };
+        }
+
+        // Resolved entirely while the heaps lock (and `guard`) are held, so the
+        // follow-up calls below always run after both are released.
+        let outcome = {
+            let mut heaps = self.heaps.lock();
+            match heaps.iter_mut().find(|heap| heap.owns(ptr)) {
+                Some(heap) => {
+                    if heap.try_grow(ptr, new_size, align) {
+                        Outcome::GrewInPlace
+                    } else {
+                        let old_real_size = match heap.allocations.get(&(ptr as usize)) {
+                            Some(allocation) => allocation.real_size,
+                            None => 0,
+                        };
+                        Outcome::FallBack(old_real_size)
+                    }
+                }
+                None => Outcome::NotOwned,
+            }
+        };
+        drop(guard);
+
+        let old_real_size = match outcome {
+            Outcome::GrewInPlace => return ptr,
+            Outcome::NotOwned => return self.allocate_aligned(new_size, align),
+            Outcome::FallBack(old_real_size) => old_real_size,
+        };
+
+        let new_ptr = self.allocate_aligned(new_size, align);
+        if new_ptr.is_null() {
+            return new_ptr;
+        }
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                ptr as *const u8,
+                new_ptr as *mut u8,
+                old_real_size.min(new_size),
+            );
         }
-        return self.heaps.first_mut().unwrap().allocate(size);
+        self.free(ptr);
+        new_ptr
+    }
+
+    /// Resizes `ptr` (previously returned by `allocate`/`allocate_aligned`) to
+    /// `new_size`, at the default alignment. Backs `heap_realloc`.
+    pub fn reallocate(&self, ptr: *mut c_void, new_size: size_t) -> *mut c_void {
+        self.realloc_aligned(ptr, DEFAULT_ALIGN, new_size)
     }
 
-    pub fn free(&mut self, ptr: *mut c_void) {
-        if self.heaps.len() == 0 {
-            self.init_heap(32768);
+    /// Allocates `nmemb * size` zeroed bytes, failing (returning null) on
+    /// multiplication overflow rather than wrapping. Backs `heap_calloc`.
+    pub fn calloc(&self, nmemb: size_t, size: size_t) -> *mut c_void {
+        let total = match nmemb.checked_mul(size) {
+            Some(total) => total,
+            None => return std::ptr::null_mut(),
+        };
+        let ptr = self.allocate(total);
+        if !ptr.is_null() {
+            unsafe {
+                std::ptr::write_bytes(ptr as *mut u8, 0, total);
+            }
         }
-        return self.heaps.first_mut().unwrap().free(ptr);
+        ptr
+    }
+}
+
+unsafe impl GlobalAlloc for HeapAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.allocate_aligned(layout.size(), layout.align()) as *mut u8
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
+        self.free(ptr as *mut c_void);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.realloc_aligned(ptr as *mut c_void, layout.align(), new_size) as *mut u8
     }
 }
 
 impl Debug for HeapAllocator {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
-        write!(f, "HeapAllocator {{ heap_count: {}}}", self.heaps.len())?;
-        for heap in self.heaps.iter() {
+        let heaps = self.heaps.lock();
+        write!(f, "HeapAllocator {{ heap_count: {}}}", heaps.len())?;
+        for heap in heaps.iter() {
             write!(f, "\n\t{:?}", heap)?;
         }
         Ok(())
@@ -183,7 +502,7 @@ mod tests {
 
     fn ensure_heap_contiguity(heap: &Heap) {
         let mut mut_ptr = heap.ptr;
-        for alloc in heap.allocations.iter() {
+        for alloc in heap.allocations.values() {
             assert!(mut_ptr == alloc.ptr);
             mut_ptr = unsafe { mut_ptr.add(alloc.alloc_size) };
         }
@@ -191,33 +510,35 @@ mod tests {
 
     #[test]
     fn allocate_all() {
-        let mut heap = HeapAllocator { heaps: vec![] };
+        let heap = HeapAllocator::new();
         for _ in 0..16 {
             assert!(!heap.allocate(2048).is_null());
         }
     }
 
     #[test]
-    fn allocate_too_many() {
-        let mut heap = HeapAllocator { heaps: vec![] };
+    fn allocate_beyond_first_heap_grows_a_new_one() {
+        let heap = HeapAllocator::new();
         for _ in 0..16 {
             assert!(!heap.allocate(2048).is_null());
         }
-        assert!(heap.allocate(2048).is_null());
+        // The first heap is exactly full; the allocator should grow instead of failing.
+        assert!(!heap.allocate(2048).is_null());
+        assert!(heap.heaps.lock().len() > 1);
     }
 
     #[test]
     fn ensure_allocations_are_contiguous() {
-        let mut heap = HeapAllocator { heaps: vec![] };
+        let heap = HeapAllocator::new();
         for _ in 0..16 {
             assert!(!heap.allocate(2048).is_null());
         }
-        ensure_heap_contiguity(heap.heaps.first().unwrap());
+        ensure_heap_contiguity(heap.heaps.lock().first().unwrap());
     }
 
     #[test]
     fn allocate_then_free() {
-        let mut heap = HeapAllocator { heaps: vec![] };
+        let heap = HeapAllocator::new();
         let mut tracked_alloc = None;
         for i in 0..16 {
             let alloc = heap.allocate(2048);
@@ -229,12 +550,12 @@ mod tests {
 
         assert!(tracked_alloc.is_some());
         heap.free(tracked_alloc.unwrap());
-        assert!(heap.heaps.first().unwrap().allocations.len() == 15);
+        assert!(heap.heaps.lock().first().unwrap().allocations.len() == 15);
     }
 
     #[test]
     fn allocate_then_free_then_allocate() {
-        let mut heap = HeapAllocator { heaps: vec![] };
+        let heap = HeapAllocator::new();
         let mut tracked_alloc = None;
         for i in 0..16 {
             let alloc = heap.allocate(2048);
@@ -247,6 +568,173 @@ mod tests {
         heap.free(tracked_alloc.unwrap());
         let alloc = heap.allocate(2048);
         assert!(alloc == tracked_alloc.unwrap());
-        ensure_heap_contiguity(heap.heaps.first().unwrap());
+        ensure_heap_contiguity(heap.heaps.lock().first().unwrap());
+    }
+
+    #[test]
+    fn global_alloc_alloc_and_dealloc() {
+        let allocator = HeapAllocator::new();
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            assert!(!ptr.is_null());
+            assert!((ptr as usize).is_multiple_of(layout.align()));
+            allocator.dealloc(ptr, layout);
+        }
+    }
+
+    #[test]
+    fn global_alloc_realloc_grows_in_place() {
+        let allocator = HeapAllocator::new();
+        let layout = Layout::from_size_align(64, 4).unwrap();
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            assert!(!ptr.is_null());
+            let grown = allocator.realloc(ptr, layout, 128);
+            assert!(grown == ptr);
+        }
+    }
+
+    #[test]
+    fn allocate_aligned_honors_large_alignment() {
+        let heap = HeapAllocator::new();
+        for align in [8usize, 16, 64, 256] {
+            let ptr = heap.allocate_aligned(37, align);
+            assert!(!ptr.is_null());
+            assert!((ptr as usize).is_multiple_of(align));
+        }
+    }
+
+    #[test]
+    fn heap_memalign_matches_api() {
+        let heap = HeapAllocator::new();
+        let ptr = heap.allocate_aligned(16, 32);
+        assert!((ptr as usize).is_multiple_of(32));
+        heap.free(ptr);
+    }
+
+    #[test]
+    fn free_handles_padded_alignment_via_user_pointer_key() {
+        let mut heap = Heap::new(4096);
+        let ptr = heap.allocate_aligned(16, 64);
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % 64, 0);
+
+        heap.free(ptr);
+        assert!(heap.allocations.is_empty());
+        assert_eq!(heap.free_space(), 4096);
+    }
+
+    #[test]
+    fn set_fit_strategy_applies_to_heaps_created_by_growth() {
+        let allocator = HeapAllocator::new();
+        allocator.set_fit_strategy(FitStrategy::BestFit);
+
+        // Force growth past the first heap; the new heap should inherit BestFit too.
+        for _ in 0..17 {
+            assert!(!allocator.allocate(2048).is_null());
+        }
+
+        let heaps = allocator.heaps.lock();
+        assert!(heaps.iter().all(|heap| heap.fit_strategy == FitStrategy::BestFit));
+    }
+
+    #[test]
+    fn largest_free_block_reports_across_all_heaps() {
+        let allocator = HeapAllocator::new();
+        assert_eq!(allocator.largest_free_block(), 0);
+
+        allocator.init_heap(4096);
+        assert_eq!(allocator.largest_free_block(), 4096);
+    }
+
+    #[test]
+    fn heap_memalign_rejects_invalid_alignment() {
+        let heap = HeapAllocator::new();
+        assert!(heap.allocate_aligned(64, 0).is_null());
+        assert!(heap.allocate_aligned(64, 3).is_null());
+    }
+
+    #[test]
+    fn freeing_adjacent_allocations_coalesces_the_hole() {
+        let mut heap = Heap::new(32768);
+        let a = heap.allocate_aligned(1024, DEFAULT_ALIGN);
+        let b = heap.allocate_aligned(1024, DEFAULT_ALIGN);
+        let c = heap.allocate_aligned(1024, DEFAULT_ALIGN);
+        assert!(!a.is_null() && !b.is_null() && !c.is_null());
+
+        let free_before = heap.free_space();
+        heap.free(a);
+        heap.free(b);
+        // a and b are adjacent, so freeing both should merge into one hole
+        // rather than leaving two 1024-byte fragments.
+        assert!(heap.largest_free_block() >= 2048);
+        assert_eq!(heap.free_regions.get(&(a as usize)), Some(&2048));
+        assert_eq!(heap.free_space(), free_before + 2048);
+    }
+
+    #[test]
+    fn free_locates_the_owning_heap_after_growth() {
+        let heap = HeapAllocator::new();
+        for _ in 0..16 {
+            assert!(!heap.allocate(2048).is_null());
+        }
+        // This allocation lands in the freshly grown second heap.
+        let grown_alloc = heap.allocate(2048);
+        assert!(!grown_alloc.is_null());
+        assert!(heap.heaps.lock().len() > 1);
+
+        heap.free(grown_alloc);
+        assert!(heap.heaps.lock().get(1).unwrap().allocations.is_empty());
+    }
+
+    #[test]
+    fn calloc_zeroes_memory_and_rejects_overflow() {
+        let heap = HeapAllocator::new();
+        let ptr = heap.calloc(16, 4) as *mut u8;
+        assert!(!ptr.is_null());
+        for i in 0..64 {
+            assert_eq!(unsafe { *ptr.add(i) }, 0);
+        }
+
+        assert!(heap.calloc(usize::MAX, 2).is_null());
+    }
+
+    #[test]
+    fn realloc_preserves_contents_when_it_has_to_move() {
+        let heap = HeapAllocator::new();
+        let ptr = heap.allocate(16) as *mut u8;
+        assert!(!ptr.is_null());
+        // Occupy the bytes right after `ptr` so it cannot grow in place.
+        let blocker = heap.allocate(16);
+        assert!(!blocker.is_null());
+        unsafe {
+            std::ptr::write_bytes(ptr, 0xAB, 16);
+        }
+
+        let grown = heap.reallocate(ptr as *mut c_void, 4096) as *mut u8;
+        assert!(!grown.is_null());
+        assert_ne!(grown, ptr);
+        for i in 0..16 {
+            assert_eq!(unsafe { *grown.add(i) }, 0xAB);
+        }
+    }
+
+    #[test]
+    fn best_fit_picks_the_smallest_region_that_fits() {
+        let mut heap = Heap::new(32768);
+        heap.set_fit_strategy(FitStrategy::BestFit);
+
+        let a = heap.allocate_aligned(4096, DEFAULT_ALIGN);
+        let b = heap.allocate_aligned(256, DEFAULT_ALIGN);
+        let c = heap.allocate_aligned(4096, DEFAULT_ALIGN);
+        assert!(!a.is_null() && !b.is_null() && !c.is_null());
+
+        heap.free(a);
+        heap.free(c);
+        // Two holes now exist: one 4096 bytes, one (tail) much larger. An
+        // allocation that fits both should land in the smaller, tighter one.
+        let fitted = heap.allocate_aligned(4096, DEFAULT_ALIGN);
+        assert_eq!(fitted, a);
     }
 }
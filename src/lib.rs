@@ -1,22 +1,55 @@
-use heap_allocator::HeapAllocator;
+use heap_allocator::{FitStrategy, HeapAllocator};
 pub use libc::{c_void, size_t};
 mod heap_allocator;
+mod memory_source;
+mod reentrancy_guard;
+mod spin_lock;
 
-static mut ALLOCATOR: HeapAllocator = HeapAllocator { heaps: vec![] };
+static ALLOCATOR: HeapAllocator = HeapAllocator::new();
 
 #[no_mangle]
 pub extern "C" fn heap_init(size: size_t) {
-    unsafe { ALLOCATOR.init_heap(size) }
+    ALLOCATOR.init_heap(size)
 }
 
 #[no_mangle]
 pub extern "C" fn heap_malloc(size: size_t) -> *mut c_void {
-    unsafe { ALLOCATOR.allocate(size) }
+    ALLOCATOR.allocate(size)
 }
 
 #[no_mangle]
 pub extern "C" fn heap_free(ptr: *mut c_void) {
-    unsafe { ALLOCATOR.free(ptr) }
+    ALLOCATOR.free(ptr)
+}
+
+#[no_mangle]
+pub extern "C" fn heap_memalign(align: size_t, size: size_t) -> *mut c_void {
+    ALLOCATOR.allocate_aligned(size, align)
+}
+
+#[no_mangle]
+pub extern "C" fn heap_calloc(nmemb: size_t, size: size_t) -> *mut c_void {
+    ALLOCATOR.calloc(nmemb, size)
+}
+
+#[no_mangle]
+pub extern "C" fn heap_realloc(ptr: *mut c_void, new_size: size_t) -> *mut c_void {
+    ALLOCATOR.reallocate(ptr, new_size)
+}
+
+#[no_mangle]
+pub extern "C" fn heap_set_fit_strategy(best_fit: bool) {
+    let strategy = if best_fit {
+        FitStrategy::BestFit
+    } else {
+        FitStrategy::FirstFit
+    };
+    ALLOCATOR.set_fit_strategy(strategy)
+}
+
+#[no_mangle]
+pub extern "C" fn heap_largest_free_block() -> size_t {
+    ALLOCATOR.largest_free_block()
 }
 
 #[cfg(test)]
@@ -28,8 +61,8 @@ mod tests {
         for _ in 0..16 {
             assert!(!heap_malloc(2048).is_null());
         }
-        println!("allocator {:?}", unsafe { &ALLOCATOR });
-        println!("final malloc {:?}", heap_malloc(2048) as usize);
-        assert!(heap_malloc(2048).is_null());
+        println!("allocator {:?}", &ALLOCATOR);
+        // The allocator grows a new heap instead of failing once the first is full.
+        assert!(!heap_malloc(2048).is_null());
     }
 }
@@ -0,0 +1,27 @@
+pub use libc::{c_void, size_t};
+
+/// Abstracts where a `Heap` gets its backing bytes from, so `Heap::new` isn't
+/// hard-wired to `libc::malloc`.
+pub trait MemorySource {
+    /// Reserves `size` bytes and returns a pointer to the start, or null on failure.
+    fn reserve(&self, size: size_t) -> *mut c_void;
+}
+
+/// Default backing store: straight off `libc::malloc`.
+#[derive(Default)]
+pub struct LibcMemorySource;
+
+impl MemorySource for LibcMemorySource {
+    fn reserve(&self, size: size_t) -> *mut c_void {
+        unsafe { libc::malloc(size) }
+    }
+}
+
+pub type DefaultMemorySource = LibcMemorySource;
+
+// A `jemalloc`-backed `MemorySource` (gated behind a `jemalloc` cargo feature)
+// was implemented here, but this tree has no `Cargo.toml` to declare the
+// feature or the optional `jemalloc-sys` dependency it needs, so there's
+// nowhere to wire it in. It was removed rather than left to bit-rot
+// uncompiled; reinstate it (add `jemalloc_sys::malloc` behind
+// `#[cfg(feature = "jemalloc")]`) once this crate gains a manifest.
@@ -0,0 +1,58 @@
+use std::cell::Cell;
+
+thread_local! {
+    /// Set for the duration of any `HeapAllocator` call that can allocate or
+    /// free while holding the heaps lock, so nested allocator calls on the same thread (made by `Vec`/
+    /// `BTreeMap` growing their own backing storage) can tell they're nested.
+    static IN_ALLOCATOR: Cell<bool> = const { Cell::new(false) };
+}
+
+/// RAII marker for "this thread is already inside a locked `HeapAllocator`
+/// section". `Heap`'s bookkeeping (`Vec<Heap>`, `BTreeMap`) is itself backed by
+/// the global allocator, so growing it while the heaps lock is held would
+/// otherwise recurse straight back into `HeapAllocator` and spin forever on a
+/// lock it's already holding. Callers check [`ReentrancyGuard::is_reentrant`]
+/// and route such nested allocations to the system allocator instead.
+pub struct ReentrancyGuard {
+    already_active: bool,
+}
+
+impl ReentrancyGuard {
+    /// Marks this thread as inside the allocator, for as long as the guard lives.
+    pub fn enter() -> ReentrancyGuard {
+        let already_active = IN_ALLOCATOR.with(|flag| flag.replace(true));
+        ReentrancyGuard { already_active }
+    }
+
+    /// Whether this call is nested inside another one on the same thread.
+    pub fn is_reentrant(&self) -> bool {
+        self.already_active
+    }
+}
+
+impl Drop for ReentrancyGuard {
+    fn drop(&mut self) {
+        if !self.already_active {
+            IN_ALLOCATOR.with(|flag| flag.set(false));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_enter_reports_reentrant_until_outer_guard_drops() {
+        let outer = ReentrancyGuard::enter();
+        assert!(!outer.is_reentrant());
+
+        let inner = ReentrancyGuard::enter();
+        assert!(inner.is_reentrant());
+        drop(inner);
+
+        drop(outer);
+        let fresh = ReentrancyGuard::enter();
+        assert!(!fresh.is_reentrant());
+    }
+}